@@ -5,6 +5,109 @@ const BMP280_ADDR: u16 = 0x76; // Default I2C address
 const REG_CALIBRATION_START: u8 = 0x88; // Start of Calibration Register
 const REG_TEMPERATURE_START: u8 = 0xFA; // Start of Temperature Register
 const REG_PRESSURE_START: u8 = 0xF7; // Start of Pressure Register
+const REG_CTRL_MEAS: u8 = 0xF4; // Oversampling + power mode
+const REG_CONFIG: u8 = 0xF5; // Standby time + IIR filter
+
+/// Oversampling setting for a measurement, register code 001..101 (datasheet table 5/6).
+#[derive(Clone, Copy)]
+pub enum Oversampling {
+    X1 = 0b001,
+    X2 = 0b010,
+    X4 = 0b011,
+    X8 = 0b100,
+    X16 = 0b101,
+}
+
+/// IIR filter coefficient, `config` register bits[4:2].
+#[derive(Clone, Copy)]
+pub enum IirFilter {
+    Off = 0b000,
+    Coeff2 = 0b001,
+    Coeff4 = 0b010,
+    Coeff8 = 0b011,
+    Coeff16 = 0b100,
+}
+
+/// Inactive duration between measurements in Normal mode, `config` register bits[7:5].
+#[derive(Clone, Copy)]
+pub enum StandbyTime {
+    Ms0_5 = 0b000,
+    Ms62_5 = 0b001,
+    Ms125 = 0b010,
+    Ms250 = 0b011,
+    Ms500 = 0b100,
+    Ms1000 = 0b101,
+    Ms2000 = 0b110,
+    Ms4000 = 0b111,
+}
+
+/// Power mode, `ctrl_meas` register bits[1:0].
+#[derive(Clone, Copy)]
+pub enum PowerMode {
+    Sleep = 0b00,
+    Forced = 0b01,
+    Normal = 0b11,
+}
+
+/// Configuration written to `ctrl_meas` (0xF4) and `config` (0xF5) during `initialize`.
+/// Defaults to indoor-weather-station settings: x2 temperature / x16 pressure
+/// oversampling, IIR filter 16, and Normal power mode.
+pub struct BMP280Config {
+    pub temperature_oversampling: Oversampling,
+    pub pressure_oversampling: Oversampling,
+    pub filter: IirFilter,
+    pub standby_time: StandbyTime,
+    pub mode: PowerMode,
+}
+
+impl Default for BMP280Config {
+    fn default() -> Self {
+        BMP280Config {
+            temperature_oversampling: Oversampling::X2,
+            pressure_oversampling: Oversampling::X16,
+            filter: IirFilter::Coeff16,
+            standby_time: StandbyTime::Ms0_5,
+            mode: PowerMode::Normal,
+        }
+    }
+}
+
+impl BMP280Config {
+    pub fn temperature_oversampling(mut self, value: Oversampling) -> Self {
+        self.temperature_oversampling = value;
+        self
+    }
+
+    pub fn pressure_oversampling(mut self, value: Oversampling) -> Self {
+        self.pressure_oversampling = value;
+        self
+    }
+
+    pub fn filter(mut self, value: IirFilter) -> Self {
+        self.filter = value;
+        self
+    }
+
+    pub fn standby_time(mut self, value: StandbyTime) -> Self {
+        self.standby_time = value;
+        self
+    }
+
+    pub fn mode(mut self, value: PowerMode) -> Self {
+        self.mode = value;
+        self
+    }
+
+    fn ctrl_meas(&self) -> u8 {
+        ((self.temperature_oversampling as u8) << 5)
+            | ((self.pressure_oversampling as u8) << 2)
+            | (self.mode as u8)
+    }
+
+    fn config(&self) -> u8 {
+        ((self.standby_time as u8) << 5) | ((self.filter as u8) << 2)
+    }
+}
 
 struct Calibration {
     dig_t1: u16,
@@ -22,6 +125,10 @@ struct Calibration {
     dig_p9: i16,
 }
 
+/// Standard atmosphere sea-level pressure in hPa, used by `read_altitude` when the
+/// caller has no local QNH.
+pub const DEFAULT_SEA_LEVEL_HPA: f32 = 1013.25;
+
 pub struct BMP280 {
     pub i2c: I2c,
     t_fine: i32,
@@ -66,8 +173,18 @@ impl BMP280 {
         Ok(())
     }
 
-    pub fn intialize(&mut self) -> Result<(), Error> {
+    pub fn intialize(&mut self, config: BMP280Config) -> Result<(), Error> {
         self.read_calibration()?;
+
+        // config (0xF5) is ignored by the chip once ctrl_meas (0xF4) has put it into
+        // Normal mode, so it must be written first while the chip is still asleep.
+        self.i2c
+            .write(&[REG_CONFIG, config.config()])
+            .expect("Failed to write config during init");
+        self.i2c
+            .write(&[REG_CTRL_MEAS, config.ctrl_meas()])
+            .expect("Failed to write ctrl_meas during init");
+
         self.read_temperature()?;
         Ok(())
     }
@@ -105,7 +222,7 @@ impl BMP280 {
 
         let mut buffer = [0u8; 3];
         self.i2c
-            .write_read(&[REG_CALIBRATION_START], &mut buffer)
+            .write_read(&[REG_PRESSURE_START], &mut buffer)
             .expect("Pressure read failed during I2C");
 
         let raw_press =
@@ -137,4 +254,31 @@ impl BMP280 {
         let pressure_hpa = (pressure as f32) / 25600.0;
         Ok(pressure_hpa)
     }
+
+    /// International barometric formula: altitude above `sea_level_hpa` for a
+    /// given compensated pressure reading, both in hPa. Pure calculation, no I2C
+    /// access, so callers that already have a pressure reading in hand (e.g. from
+    /// `read_pressure()`) can derive altitude from it without triggering another
+    /// round of temperature/pressure reads.
+    pub fn altitude_from_pressure(pressure_hpa: f32, sea_level_hpa: f32) -> f32 {
+        44330.0 * (1.0 - (pressure_hpa / sea_level_hpa).powf(1.0 / 5.255))
+    }
+
+    /// Barometric altitude above `sea_level_hpa`, computed from the current
+    /// pressure reading via the international barometric formula. Pass
+    /// `DEFAULT_SEA_LEVEL_HPA` when no local QNH is known.
+    pub fn read_altitude(&mut self, sea_level_hpa: f32) -> Result<f32, Error> {
+        let pressure_hpa = self.read_pressure()?;
+        Ok(Self::altitude_from_pressure(pressure_hpa, sea_level_hpa))
+    }
+
+    /// Back-solve the local sea-level reference pressure (QNH, in hPa) from the
+    /// current pressure reading and a known altitude. Takes a fresh pressure reading
+    /// over I2C; the result is returned, not stored, so the caller must pass it into
+    /// `read_altitude` as `sea_level_hpa` themselves.
+    pub fn compute_sea_level_pressure(&mut self, known_altitude_m: f32) -> Result<f32, Error> {
+        let pressure_hpa = self.read_pressure()?;
+        let sea_level_hpa = pressure_hpa / (1.0 - known_altitude_m / 44330.0).powf(5.255);
+        Ok(sea_level_hpa)
+    }
 }
@@ -1,5 +1,5 @@
 use rppal::gpio::{Gpio, OutputPin};
-use rppal::i2c::I2c;
+use rppal::i2c::{Error as I2CError, I2c};
 use std::cmp::min;
 use std::fmt::{self, write};
 use std::io::Error;
@@ -13,6 +13,8 @@ pub enum Ccs811Mode {
     Sec1 = 1,
     Sec10 = 2,
     Sec60 = 3,
+    /// Constant power mode, samples every 250 ms. Used for raw-data streaming.
+    Ms250 = 4,
 }
 
 pub const CCS811_SLAVEADDR_0: u16 = 0x5A;
@@ -39,10 +41,48 @@ pub const CCS811_STATUS_APP_MODE: u8 = 0b10000000; // Else boot mode
 pub const CCS811_STATUS_APP_ERASE: u8 = 0b01000000; // Else no erase completed
 pub const CCS811_STATUS_APP_VERIFY: u8 = 0b00100000; // Else no verify completed
 pub const CCS811_STATUS_APP_VALID: u8 = 0b00010000; // Else no valid app firmware loaded
+pub const CCS811_STATUS_ERROR: u8 = 0b00000001; // Set when an error is present, see CCS811_ERR
+pub const CCS811_STATUS_DATA_READY: u8 = 0b00001000; // Set when new data is available to read
+pub const CCS811_MEAS_MODE_INTERRUPT: u8 = 0b00001000; // Assert nINT on new data when set
+
+// CCS811_ERR (ERROR_ID) bits
+pub const CCS811_ERR_WRITE_REG_INVALID: u8 = 0b00000001;
+pub const CCS811_ERR_READ_REG_INVALID: u8 = 0b00000010;
+pub const CCS811_ERR_MEASMODE_INVALID: u8 = 0b00000100;
+pub const CCS811_ERR_MAX_RESISTANCE: u8 = 0b00001000;
+pub const CCS811_ERR_HEATER_FAULT: u8 = 0b00010000;
+pub const CCS811_ERR_HEATER_SUPPLY: u8 = 0b00100000;
+
+/// Decoded bits of the CCS811_ERR (ERROR_ID) register, valid whenever
+/// `CCS811_STATUS_ERROR` is set in STATUS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ccs811Error {
+    WriteRegInvalid,
+    ReadRegInvalid,
+    MeasModeInvalid,
+    MaxResistance,
+    HeaterFault,
+    HeaterSupply,
+}
+
+impl fmt::Display for Ccs811Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            Ccs811Error::WriteRegInvalid => "invalid register address written",
+            Ccs811Error::ReadRegInvalid => "invalid register address read",
+            Ccs811Error::MeasModeInvalid => "invalid requested measurement mode",
+            Ccs811Error::MaxResistance => "sensor resistance measurement at its max range",
+            Ccs811Error::HeaterFault => "heater current not in range",
+            Ccs811Error::HeaterSupply => "heater voltage not being applied correctly",
+        };
+        write!(f, "{}", message)
+    }
+}
 
 pub const CCS811_WAIT_AFTER_RESET_US: Duration = Duration::from_micros(2000); // The CCS811 needs a wait after reset
 pub const CCS811_WAIT_AFTER_APPSTART_US: Duration = Duration::from_micros(1000); // The CCS811 needs a wait after app start
 pub const CCS811_WAIT_AFTER_WAKE_US: Duration = Duration::from_micros(50); // The CCS811 needs a wait after WAKE signal
+pub const CCS811_WAIT_AFTER_DWAKE_US: Duration = Duration::from_micros(20); // The CCS811 needs a wait after releasing WAKE
 pub const CCS811_WAIT_AFTER_APPERASE_MS: Duration = Duration::from_millis(500); // The CCS811 needs a wait after app erase (300ms from spec not enough)
 pub const CCS811_WAIT_AFTER_APPVERIFY_MS: Duration = Duration::from_millis(70); // The CCS811 needs a wait after app verify
 pub const CCS811_WAIT_AFTER_APPDATA_MS: Duration = Duration::from_millis(50); // The CCS811 needs a wait after writing app data
@@ -67,21 +107,85 @@ pub struct Ccs811Data {
     pub t_voc: u32,
     pub e_co2: u32,
     pub raw: Vec<u8>,
+    /// Raw sensor current in µA, decoded from CCS811_ALG_RESULT_DATA byte 6 bits[7:2].
+    /// Most useful alongside `Ccs811Mode::Ms250` raw-data streaming.
+    pub raw_current: u16,
+    /// Raw ADC voltage reading (10 bits), decoded from CCS811_ALG_RESULT_DATA bytes 6-7.
+    pub raw_voltage: u16,
 }
 
 pub struct CCS811 {
     pub i2c: I2c,
+    wake: Option<OutputPin>,
 }
 // ------------------------------------------------------------------------
 
 impl CCS811 {
     pub fn new(i2c: I2c) -> CCS811 {
-        CCS811 { i2c }
+        CCS811 { i2c, wake: None }
+    }
+
+    /// Same as `new_with_wake` but opens the nWAKE pin from a BCM GPIO number
+    /// instead of requiring an already-configured `OutputPin`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let gpio = rppal::gpio::Gpio::new().expect("Failed to access GPIO");
+    /// let ccs811 = ccs811::CCS811::new_with_wake_pin(i2c, &gpio, 23)
+    ///     .expect("Failed to open nWAKE pin");
+    /// ```
+    pub fn new_with_wake_pin(i2c: I2c, gpio: &Gpio, bcm_pin: u8) -> Result<CCS811, String> {
+        let wake = gpio
+            .get(bcm_pin)
+            .map_err(|error| format!("Could not open nWAKE pin {}: {}", bcm_pin, error))?
+            .into_output();
+
+        Ok(Self::new_with_wake(i2c, wake))
+    }
+
+    /// Same as `new` but drives the given pin as the nWAKE line around every I2C
+    /// transaction instead of assuming it is tied low on the board.
+    pub fn new_with_wake(i2c: I2c, wake: OutputPin) -> CCS811 {
+        CCS811 {
+            i2c,
+            wake: Some(wake),
+        }
+    }
+
+    /// Pull nWAKE low and wait t_WAKE (50 µs) for the chip to settle. No-op if no
+    /// wake pin was supplied, since the board then ties nWAKE low permanently.
+    fn wake_low(&mut self) {
+        if let Some(wake) = self.wake.as_mut() {
+            wake.set_low();
+            sleep(CCS811_WAIT_AFTER_WAKE_US);
+        }
+    }
+
+    /// Release nWAKE high and wait t_DWAKE (20 µs) before the next transaction.
+    fn wake_high(&mut self) {
+        if let Some(wake) = self.wake.as_mut() {
+            wake.set_high();
+            sleep(CCS811_WAIT_AFTER_DWAKE_US);
+        }
+    }
+
+    /// Run an I2C transaction with nWAKE pulled low for its duration, releasing it
+    /// again afterwards even if `f` returns an error. Centralizes the
+    /// wake/transfer/unwake sequence every I2C access needs so call sites can't
+    /// forget to release nWAKE on an early return.
+    fn with_wake<T>(
+        &mut self,
+        f: impl FnOnce(&mut I2c) -> Result<T, I2CError>,
+    ) -> Result<T, I2CError> {
+        self.wake_low();
+        let result = f(&mut self.i2c);
+        self.wake_high();
+        result
     }
 
     fn reset(&mut self) -> Result<(), String> {
-        self.i2c
-            .block_write(CCS811_SW_RESET, &[0x11, 0xE5, 0x72, 0x8A])
+        self.with_wake(|i2c| i2c.block_write(CCS811_SW_RESET, &[0x11, 0xE5, 0x72, 0x8A]))
             .map_err(|error| format!("Couldn't write to I2C: {}", error))?;
 
         sleep(CCS811_WAIT_AFTER_RESET_US);
@@ -90,8 +194,7 @@ impl CCS811 {
     }
 
     fn app_start(&mut self) -> Result<(), String> {
-        self.i2c
-            .write(&[CCS811_APP_START])
+        self.with_wake(|i2c| i2c.write(&[CCS811_APP_START]))
             .map_err(|error| format!("Could not set App start: {}", error))?;
 
         sleep(CCS811_WAIT_AFTER_APPSTART_US);
@@ -100,8 +203,7 @@ impl CCS811 {
     }
 
     fn erase_app(&mut self) -> Result<(), String> {
-        self.i2c
-            .block_write(CCS811_APP_ERASE, &[0xE7, 0xA7, 0xE6, 0x09])
+        self.with_wake(|i2c| i2c.block_write(CCS811_APP_ERASE, &[0xE7, 0xA7, 0xE6, 0x09]))
             .map_err(|error| format!("Could not erase app: {}", error))?;
 
         sleep(CCS811_WAIT_AFTER_APPERASE_MS);
@@ -111,8 +213,7 @@ impl CCS811 {
 
     fn check_hw_id(&mut self) -> Result<(), String> {
         let hw_id = self
-            .i2c
-            .smbus_read_byte(CCS811_HW_ID)
+            .with_wake(|i2c| i2c.smbus_read_byte(CCS811_HW_ID))
             .map_err(|error| format!("Couldn't read HWID: {}", error))?;
 
         if hw_id != 0x81 {
@@ -124,8 +225,7 @@ impl CCS811 {
 
     fn check_status(&mut self, expected: u8) -> Result<(), String> {
         let status = self
-            .i2c
-            .smbus_read_byte(CCS811_STATUS)
+            .with_wake(|i2c| i2c.smbus_read_byte(CCS811_STATUS))
             .map_err(|error| format!("Could not read chip status: {}", error))?;
 
         if (status & expected) == 0 {
@@ -138,6 +238,76 @@ impl CCS811 {
         Ok(())
     }
 
+    /// Decode a CCS811_ERR (ERROR_ID) byte into the set of `Ccs811Error`s it
+    /// reports. Pure bit-mapping, no I2C access, so callers that already have the
+    /// byte in hand (e.g. `read()`'s ALG_RESULT_DATA block) don't need to re-query
+    /// the chip just to interpret it.
+    fn decode_error_id(error_id: u8) -> Vec<Ccs811Error> {
+        let mut errors = Vec::new();
+        if error_id & CCS811_ERR_WRITE_REG_INVALID != 0 {
+            errors.push(Ccs811Error::WriteRegInvalid);
+        }
+        if error_id & CCS811_ERR_READ_REG_INVALID != 0 {
+            errors.push(Ccs811Error::ReadRegInvalid);
+        }
+        if error_id & CCS811_ERR_MEASMODE_INVALID != 0 {
+            errors.push(Ccs811Error::MeasModeInvalid);
+        }
+        if error_id & CCS811_ERR_MAX_RESISTANCE != 0 {
+            errors.push(Ccs811Error::MaxResistance);
+        }
+        if error_id & CCS811_ERR_HEATER_FAULT != 0 {
+            errors.push(Ccs811Error::HeaterFault);
+        }
+        if error_id & CCS811_ERR_HEATER_SUPPLY != 0 {
+            errors.push(Ccs811Error::HeaterSupply);
+        }
+
+        errors
+    }
+
+    /// Read STATUS and, if its ERROR bit is set, read CCS811_ERR and decode it into
+    /// the set of `Ccs811Error`s the chip is reporting. Returns an empty `Vec` when
+    /// no error is flagged. Works in both boot and app mode.
+    fn decode_errors(&mut self) -> Result<Vec<Ccs811Error>, String> {
+        let status = self
+            .with_wake(|i2c| i2c.smbus_read_byte(CCS811_STATUS))
+            .map_err(|error| format!("Could not read chip status: {}", error))?;
+
+        if status & CCS811_STATUS_ERROR == 0 {
+            return Ok(Vec::new());
+        }
+
+        let error_id = self
+            .with_wake(|i2c| i2c.smbus_read_byte(CCS811_ERR))
+            .map_err(|error| format!("Could not read error id: {}", error))?;
+
+        Ok(Self::decode_error_id(error_id))
+    }
+
+    fn format_errors(errors: &[Ccs811Error]) -> String {
+        errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Check STATUS for the ERROR bit after a flashing stage and turn it into a
+    /// descriptive `Err` if set.
+    fn check_flash_errors(&mut self, stage: &str) -> Result<(), String> {
+        let errors = self.decode_errors()?;
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Firmware {} stage failed: {}",
+                stage,
+                Self::format_errors(&errors)
+            ))
+        }
+    }
+
     /// Initialize CCS811 chip with i2c bus
     /// Sequence: set i2c slave -> Wake to low -> reset chip -> check hardware id -> start chip -> check chip status -> Wake to high -> ready
     ///
@@ -156,10 +326,15 @@ impl CCS811 {
             .set_slave_address(CCS811_SLAVEADDR_0)
             .map_err(|error| format!("Could not set slave addr: {}", error))?;
 
-        self.reset()
-            .and(self.check_hw_id())
-            .and(self.app_start())
-            .and(self.check_status(CCS811_STATUS_APP_MODE | CCS811_STATUS_APP_VERIFY))?;
+        self.reset().and(self.check_hw_id()).and(self.app_start())?;
+
+        if let Err(error) = self.check_status(CCS811_STATUS_APP_MODE | CCS811_STATUS_APP_VERIFY) {
+            let errors = self.decode_errors()?;
+            if errors.is_empty() {
+                return Err(error);
+            }
+            return Err(format!("{} ({})", error, Self::format_errors(&errors)));
+        }
 
         Ok(())
     }
@@ -185,8 +360,7 @@ impl CCS811 {
     /// }
     /// ```
     pub fn start(&mut self, mode: Ccs811Mode) -> Result<(), String> {
-        self.i2c
-            .block_write(CCS811_MEAS_MODE, &[(mode as u8) << 4])
+        self.with_wake(|i2c| i2c.block_write(CCS811_MEAS_MODE, &[(mode as u8) << 4]))
             .map_err(|error| format!("Could not set mode: {}", error))?;
 
         Ok(())
@@ -194,16 +368,14 @@ impl CCS811 {
 
     /// Version should be something like 0x1X
     pub fn hardware_version(&mut self) -> Result<u8, String> {
-        self.i2c
-            .smbus_read_byte(CCS811_HW_VERSION)
+        self.with_wake(|i2c| i2c.smbus_read_byte(CCS811_HW_VERSION))
             .map_err(|error| format!("Could not read hardware version: {}", error))
     }
 
     /// Something like 0x10 0x0
     pub fn bootloader_version(&mut self) -> Result<[u8; 2], String> {
         let mut buffer = [0; 2];
-        self.i2c
-            .block_read(CCS811_FW_BOOT_VERSION, &mut buffer)
+        self.with_wake(|i2c| i2c.block_read(CCS811_FW_BOOT_VERSION, &mut buffer))
             .map_err(|error| format!("Could not read boot loader version: {}", error))?;
 
         Ok(buffer)
@@ -213,25 +385,66 @@ impl CCS811 {
     /// and a firmware binary. See examples for more details
     pub fn application_version(&mut self) -> Result<[u8; 2], String> {
         let mut buffer = [0; 2];
-        self.i2c
-            .block_read(CCS811_FW_APP_VERSION, &mut buffer)
+        self.with_wake(|i2c| i2c.block_read(CCS811_FW_APP_VERSION, &mut buffer))
             .map_err(|error| format!("Could not read application version: {}", error))?;
 
         Ok(buffer)
     }
 
+    /// Flash new application firmware while the chip is in boot mode (i.e. before
+    /// `app_start`/`begin` have been called). Erases the current app, streams
+    /// `firmware` to CCS811_APP_DATA in 8-byte blocks, verifies, and confirms the
+    /// APP_VALID status bit comes back set. `firmware.len()` must be a multiple of 8.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ccs811 = ccs811::CCS811::new(i2c);
+    /// ccs811.flash(&firmware_bytes).expect("Failed to flash firmware");
+    /// ```
+    pub fn flash(&mut self, firmware: &[u8]) -> Result<(), String> {
+        if firmware.len() % 8 != 0 {
+            return Err(format!(
+                "Firmware length must be a multiple of 8 bytes, got {}",
+                firmware.len()
+            ));
+        }
+
+        self.i2c
+            .set_slave_address(CCS811_SLAVEADDR_0)
+            .map_err(|error| format!("Could not set slave addr: {}", error))?;
+
+        self.erase_app()?;
+        self.check_flash_errors("erase")?;
+
+        for block in firmware.chunks(8) {
+            self.with_wake(|i2c| i2c.block_write(CCS811_APP_DATA, block))
+                .map_err(|error| format!("Could not write firmware block: {}", error))?;
+
+            sleep(CCS811_WAIT_AFTER_APPDATA_MS);
+        }
+        self.check_flash_errors("data write")?;
+
+        self.with_wake(|i2c| i2c.write(&[CCS811_APP_VERIFY]))
+            .map_err(|error| format!("Could not verify app: {}", error))?;
+
+        sleep(CCS811_WAIT_AFTER_APPVERIFY_MS);
+        self.check_flash_errors("verify")?;
+
+        self.check_status(CCS811_STATUS_APP_VALID)
+            .map_err(|error| format!("Firmware flash did not produce a valid app: {}", error))
+    }
+
     /// Get the currently used baseline
     pub fn get_baseline(&mut self) -> Result<u16, String> {
-        self.i2c
-            .smbus_read_word(CCS811_BASELINE)
+        self.with_wake(|i2c| i2c.smbus_read_word(CCS811_BASELINE))
             .map_err(|error| format!("Could not read baseline: {}", error))
     }
 
     /// The CCS811 chip has an automatic baseline correction based on a 24 hour interval but you still
     /// can set the baseline manually if you want.
     pub fn set_baseline(&mut self, baseline: u16) -> Result<(), String> {
-        self.i2c
-            .smbus_write_word(CCS811_BASELINE, baseline)
+        self.with_wake(|i2c| i2c.smbus_write_word(CCS811_BASELINE, baseline))
             .map_err(|error| format!("Could not set baseline: {}", error))
     }
 
@@ -249,8 +462,7 @@ impl CCS811 {
     pub fn set_env_data(&mut self, humidity: f32, temperature: f32) -> Result<(), String> {
         let data = [float_to_bytes(humidity), float_to_bytes(temperature)].concat();
 
-        self.i2c
-            .block_write(CCS811_ENV_DATA, &data)
+        self.with_wake(|i2c| i2c.block_write(CCS811_ENV_DATA, &data))
             .map_err(|error| format!("Could npt write env data: {}", error))?;
 
         Ok(())
@@ -272,17 +484,25 @@ impl CCS811 {
     pub fn read(&mut self) -> Result<Ccs811Data, String> {
         let mut buffer = [0; 8];
 
-        self.i2c
-            .block_read(CCS811_ALG_RESULT_DATA, &mut buffer)
+        self.with_wake(|i2c| i2c.block_read(CCS811_ALG_RESULT_DATA, &mut buffer))
             .map_err(|error| format!("Could not read chip data: {}", error))?;
 
         if buffer[5] != 0 {
-            return Err(format!("Some error while reading data {:x?}", buffer[5]));
+            // buffer[4]/buffer[5] are STATUS/ERROR_ID, already fetched above as part
+            // of ALG_RESULT_DATA, so decode them locally instead of re-querying the
+            // chip (which would also race against its error state changing).
+            let errors = Self::decode_error_id(buffer[5]);
+            if errors.is_empty() {
+                return Err(format!("Some error while reading data {:x?}", buffer[5]));
+            }
+            return Err(format!("Chip reported error(s): {}", Self::format_errors(&errors)));
         }
 
         let data = Ccs811Data {
             e_co2: (buffer[0] as u16 * 256 + buffer[1] as u16) as u32,
             t_voc: (buffer[2] as u16 * 256 + buffer[3] as u16) as u32,
+            raw_current: (buffer[6] >> 2) as u16,
+            raw_voltage: (((buffer[6] & 0b11) as u16) << 8) | buffer[7] as u16,
             raw: buffer.to_vec(),
         };
 
@@ -295,4 +515,51 @@ impl CCS811 {
 
         Ok(data)
     }
+
+    /// Read STATUS and report whether the DATA_READY bit is set, i.e. whether a new
+    /// sample is waiting to be read. Cheaper than `read()` when polling faster than
+    /// the chip's sampling rate, e.g. at `Ccs811Mode::Sec60`.
+    pub fn has_data_ready(&mut self) -> Result<bool, String> {
+        let status = self
+            .with_wake(|i2c| i2c.smbus_read_byte(CCS811_STATUS))
+            .map_err(|error| format!("Could not read chip status: {}", error))?;
+
+        Ok(status & CCS811_STATUS_DATA_READY != 0)
+    }
+
+    fn set_meas_mode_interrupt(&mut self, enabled: bool) -> Result<(), String> {
+        let current = self
+            .with_wake(|i2c| i2c.smbus_read_byte(CCS811_MEAS_MODE))
+            .map_err(|error| format!("Could not read meas mode: {}", error))?;
+
+        let updated = if enabled {
+            current | CCS811_MEAS_MODE_INTERRUPT
+        } else {
+            current & !CCS811_MEAS_MODE_INTERRUPT
+        };
+
+        self.with_wake(|i2c| i2c.block_write(CCS811_MEAS_MODE, &[updated]))
+            .map_err(|error| format!("Could not update meas mode: {}", error))
+    }
+
+    /// Set the INTERRUPT bit in MEAS_MODE so the chip asserts nINT whenever new
+    /// data is ready, as an alternative to polling `has_data_ready`.
+    pub fn enable_interrupt(&mut self) -> Result<(), String> {
+        self.set_meas_mode_interrupt(true)
+    }
+
+    /// Clear the INTERRUPT bit in MEAS_MODE.
+    pub fn disable_interrupt(&mut self) -> Result<(), String> {
+        self.set_meas_mode_interrupt(false)
+    }
+
+    /// Only perform the `read()` transfer when `has_data_ready()` reports a new
+    /// sample, returning `None` otherwise instead of re-reading stale data.
+    pub fn read_if_ready(&mut self) -> Result<Option<Ccs811Data>, String> {
+        if self.has_data_ready()? {
+            self.read().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
 }
@@ -2,13 +2,14 @@ mod bmp280;
 mod ccs811;
 mod tmp102;
 
-use ccs811::Ccs811Data;
-use prometheus_exporter::prometheus::register_gauge;
-use rppal::gpio::Gpio;
+use bmp280::BMP280Config;
+use prometheus_exporter::prometheus::{register_counter, register_gauge};
 use rppal::i2c::I2c;
 use std::net::SocketAddr;
-use std::thread::sleep;
-use std::time::Duration;
+
+// The board has no onboard humidity sensor, so the CCS811 environmental
+// compensation is fed this fixed humidity alongside the measured temperature.
+const DEFAULT_HUMIDITY_PERCENT: f32 = 50.0;
 
 fn main() {
     let i2c_voc = I2c::with_bus(1).expect("Failed to start VOC I2c!");
@@ -20,11 +21,9 @@ fn main() {
     let mut press = bmp280::BMP280::new(i2c_pressure);
 
     press
-        .intialize()
+        .intialize(BMP280Config::default())
         .expect("Failed to initialize pressure sensor");
 
-    voc.begin().expect("Could not begin VOC sensor reading ");
-
     match voc.begin() {
         Ok(()) => match voc.start(ccs811::Ccs811Mode::Sec1) {
             Ok(()) => (),
@@ -39,43 +38,75 @@ fn main() {
 
     let temp_gauge = register_gauge!("temperature", "ambient temperature in celsius")
         .expect("can not create gauge temperature");
+    let pressure_gauge = register_gauge!("pressure", "hPa").expect("can not create gauge pressure");
+    let altitude_gauge = register_gauge!("altitude", "meters above sea level")
+        .expect("can not create gauge altitude");
     let tvoc_gauge = register_gauge!("tvoc", "tVOC").expect("can not create gauge tvoc");
     let eco2_gauge = register_gauge!("eco2", "eCO2").expect("can not create gauge eCO2");
-    let pressure_gauge = register_gauge!("pressure", "hPa").expect("can not create gauge pressure");
+    let temp_errors_counter =
+        register_counter!("temperature_errors_total", "errors while reading the temperature sensor")
+            .expect("can not create counter temperature_errors_total");
+    let pressure_errors_counter =
+        register_counter!("pressure_errors_total", "errors while reading the pressure sensor")
+            .expect("can not create counter pressure_errors_total");
+    let altitude_errors_counter =
+        register_counter!("altitude_errors_total", "errors while computing altitude")
+            .expect("can not create counter altitude_errors_total");
+    let eco2_errors_counter = register_counter!("eco2_errors_total", "errors while reading the VOC sensor")
+        .expect("can not create counter eco2_errors_total");
 
     loop {
-        println!("Read VOC Sensor");
-        match voc.read() {
-            Ok(data) => {
-                println!(
-                    "t_voc: {}, e_co2: {}, raw: {:x?}",
-                    data.t_voc, data.e_co2, data.raw
-                );
-            }
-            Err(error) => println!("Could not read data: {}", error),
-        }
-
-        sleep(Duration::from_secs_f32(2.0));
-    }
+        // Will block until a new request comes in.
+        let _guard = exporter.wait_request();
+        println!("Updating metrics");
 
-    // loop {
-    //     // Will block until a new request comes in.
-    //     let _guard = exporter.wait_request();
-    //     println!("Updating metrics");
+        // Each sensor read is handled independently so that a read returning an
+        // error only drops that gauge's update for this cycle instead of panicking
+        // the whole process (and with it, metrics serving). The BMP280 driver does
+        // not return I2C failures as errors: it panics on them.
+        let curr_temp = match temp.read() {
+            Ok(value) => {
+                temp_gauge.set(value as f64);
+                Some(value)
+            }
+            Err(error) => {
+                println!("Could not read temperature: {}", error);
+                temp_errors_counter.inc();
+                None
+            }
+        };
 
-    //     let curr_temp = temp.read().unwrap() as f64;
-    //     temp_gauge.set(curr_temp);
+        // Altitude is derived from this same pressure reading below rather than
+        // calling press.read_altitude(), which would trigger a second I2C round-trip.
+        match press.read_pressure() {
+            Ok(value) => {
+                pressure_gauge.set(value as f64);
+                let altitude =
+                    bmp280::BMP280::altitude_from_pressure(value, bmp280::DEFAULT_SEA_LEVEL_HPA);
+                altitude_gauge.set(altitude as f64);
+            }
+            Err(error) => {
+                println!("Could not read pressure: {}", error);
+                pressure_errors_counter.inc();
+                altitude_errors_counter.inc();
+            }
+        }
 
-    //     let curr_pressure = press.read_pressure().unwrap() as f64;
-    //     pressure_gauge.set(curr_pressure);
+        if let Some(curr_temp) = curr_temp {
+            if let Err(error) = voc.set_env_data(DEFAULT_HUMIDITY_PERCENT, curr_temp) {
+                println!("Could not set VOC environmental data: {}", error);
+            }
+        }
 
-    //     let curr_voc = voc.read().unwrap();
-    //     // let curr_voc = Ccs811Data {
-    //     //     e_co2: 0,
-    //     //     t_voc: 0,
-    //     //     raw: vec![],
-    //     // };
-    //     tvoc_gauge.set(curr_voc.t_voc as f64);
-    //     eco2_gauge.set(curr_voc.e_co2 as f64);
-    // }
+        match voc.read() {
+            Ok(curr_voc) => {
+                tvoc_gauge.set(curr_voc.t_voc as f64);
+                eco2_gauge.set(curr_voc.e_co2 as f64);
+            }
+            Err(error) => {
+                println!("Could not read VOC data: {}", error);
+                eco2_errors_counter.inc();
+            }
+        }
+    }
 }